@@ -12,7 +12,7 @@ use ecdsa::{
 use k256::Secp256k1;
 use serde::{Deserialize, Serialize};
 
-use crate::sha256::Hash;
+use crate::sha256::Txid;
 
 // The signature serves as a proof that a particular entity has autorizhed a transaction.
 // -> The sender of the transaction is indeed the owner of the funds being spent.
@@ -27,13 +27,13 @@ pub struct Signature(ECDSASignature<Secp256k1>);
 
 impl Signature {
     // sign a TransactionOutput
-    pub fn sign_output(output_hash: &Hash, private_key: &PrivateKey) -> Self {
+    pub fn sign_output(output_hash: &Txid, private_key: &PrivateKey) -> Self {
         let signing_key = &private_key.0;
         let signature = signing_key.sign(&output_hash.as_bytes());
         Signature(signature)
     }
     // verify a signature
-    pub fn verify(&self, output_hash: &Hash, public_key: &PublicKey) -> bool {
+    pub fn verify(&self, output_hash: &Txid, public_key: &PublicKey) -> bool {
         public_key
             .0
             .verify(&output_hash.as_bytes(), &self.0)
@@ -53,6 +53,13 @@ impl PrivateKey {
         Self(SigningKey::random(&mut rand::thread_rng()))
     }
 
+    // Build a key deterministically from raw bytes, e.g. for a network's
+    // hardcoded genesis coinbase, where the key must be reproducible rather
+    // than random.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self(SigningKey::from_slice(bytes).expect("invalid private key bytes"))
+    }
+
     pub fn public_key(&self) -> PublicKey {
         PublicKey(self.0.verifying_key().clone())
     }
@@ -0,0 +1,28 @@
+/*
+A single error type shared across the crate.
+*/
+
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, BtcError>;
+
+#[derive(Debug)]
+pub enum BtcError {
+    InvalidBlock,
+    InvalidMerkleRoot,
+    InvalidTransaction,
+    InvalidAddress,
+}
+
+impl fmt::Display for BtcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BtcError::InvalidBlock => write!(f, "invalid block"),
+            BtcError::InvalidMerkleRoot => write!(f, "invalid merkle root"),
+            BtcError::InvalidTransaction => write!(f, "invalid transaction"),
+            BtcError::InvalidAddress => write!(f, "invalid address"),
+        }
+    }
+}
+
+impl std::error::Error for BtcError {}
@@ -1,4 +1,9 @@
 pub mod crypto;
+pub mod error;
+pub mod mempool;
+pub mod network;
+pub mod pow;
+pub mod script;
 pub mod sha256;
 pub mod types;
 pub mod util;
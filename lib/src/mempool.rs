@@ -0,0 +1,345 @@
+/*
+The mempool holds transactions that have been received and checked against the
+current utxo set but are not yet included in a block. Miners draw from it when
+assembling the next candidate block.
+*/
+
+use crate::error::{BtcError, Result};
+use crate::script::p2pkh_script_pubkey;
+use crate::sha256::{BlockHash, Txid};
+use crate::types::{verify_transaction, Block, BlockHeader, Transaction, TransactionOutput};
+use crate::util::MerkleRoot;
+use crate::U256;
+use chrono::Utc;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use crate::crypto::PublicKey;
+
+#[derive(Debug, Default, Clone)]
+pub struct Mempool {
+    transactions: HashMap<Txid, Transaction>,
+    // prev_transaction_output_hash -> the mempool transaction that spends it
+    spent: HashMap<Txid, Txid>,
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Self {
+            transactions: HashMap::new(),
+            spent: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    pub fn transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.transactions.values()
+    }
+
+    // a transaction's outputs are spendable by another mempool transaction
+    // before they ever land in a block, so lookups combine the utxo set with
+    // every output currently sitting in the mempool.
+    pub(crate) fn lookup(&self, utxos: &HashMap<Txid, TransactionOutput>) -> HashMap<Txid, TransactionOutput> {
+        let mut lookup = utxos.clone();
+        for transaction in self.transactions.values() {
+            for output in &transaction.outputs {
+                lookup.insert(output.hash(), output.clone());
+            }
+        }
+        lookup
+    }
+
+    // Accept `transaction` only if every input references an existing utxo or
+    // another mempool transaction's output, no input is already spent by
+    // another mempool transaction, and its signatures check out.
+    pub fn add_transaction(
+        &mut self,
+        transaction: Transaction,
+        utxos: &HashMap<Txid, TransactionOutput>,
+    ) -> Result<()> {
+        for input in &transaction.inputs {
+            if self.spent.contains_key(&input.prev_transaction_output_hash) {
+                return Err(BtcError::InvalidTransaction);
+            }
+        }
+
+        let lookup = self.lookup(utxos);
+        let mut spent_by_this_tx: HashMap<Txid, TransactionOutput> = HashMap::new();
+        let _fee = verify_transaction(&transaction, &lookup, &mut spent_by_this_tx)?;
+
+        for prev_output_hash in spent_by_this_tx.keys() {
+            self.spent.insert(*prev_output_hash, transaction.hash());
+        }
+        self.transactions.insert(transaction.hash(), transaction);
+        Ok(())
+    }
+
+    // Drop a transaction (and its reserved inputs) from the mempool, e.g.
+    // once it has been confirmed in a mined block.
+    pub fn remove_transaction(&mut self, txid: &Txid) {
+        if self.transactions.remove(txid).is_some() {
+            self.spent.retain(|_, spender| spender != txid);
+        }
+    }
+}
+
+fn serialized_len(transaction: &Transaction) -> u64 {
+    let mut bytes = vec![];
+    ciborium::into_writer(transaction, &mut bytes).expect("failed to serialize transaction");
+    bytes.len() as u64
+}
+
+fn transaction_fee(transaction: &Transaction, utxos: &HashMap<Txid, TransactionOutput>) -> u64 {
+    let input_value: u64 = transaction
+        .inputs
+        .iter()
+        .filter_map(|input| utxos.get(&input.prev_transaction_output_hash))
+        .map(|output| output.value)
+        .sum();
+    let output_value: u64 = transaction.outputs.iter().map(|output| output.value).sum();
+    input_value.saturating_sub(output_value)
+}
+
+fn fee_rate(transaction: &Transaction, utxos: &HashMap<Txid, TransactionOutput>) -> f64 {
+    transaction_fee(transaction, utxos) as f64 / serialized_len(transaction) as f64
+}
+
+// Map an output's hash to the mempool transaction that produces it, so a
+// transaction spending another mempool transaction's output (a chained
+// spend, same as add_transaction allows) can be recognized below.
+fn producers(candidates: &[&Transaction]) -> HashMap<Txid, Txid> {
+    let mut producers = HashMap::new();
+    for transaction in candidates {
+        for output in &transaction.outputs {
+            producers.insert(output.hash(), transaction.hash());
+        }
+    }
+    producers
+}
+
+// Stable topological sort: walks `candidates` in the order given (by fee
+// rate), but recurses into a transaction's in-mempool parent first, so a
+// parent always lands earlier in the result than any child spending its
+// output. Block::verify_transactions requires that order to accept a chained
+// spend.
+fn order_parents_first<'a>(
+    candidates: &[&'a Transaction],
+    producers: &HashMap<Txid, Txid>,
+) -> Vec<&'a Transaction> {
+    let by_txid: HashMap<Txid, &Transaction> = candidates
+        .iter()
+        .map(|transaction| (transaction.hash(), *transaction))
+        .collect();
+    let mut visited = HashSet::new();
+    let mut ordered = Vec::with_capacity(candidates.len());
+
+    fn visit<'a>(
+        transaction: &'a Transaction,
+        by_txid: &HashMap<Txid, &'a Transaction>,
+        producers: &HashMap<Txid, Txid>,
+        visited: &mut HashSet<Txid>,
+        ordered: &mut Vec<&'a Transaction>,
+    ) {
+        if !visited.insert(transaction.hash()) {
+            return;
+        }
+        for input in &transaction.inputs {
+            if let Some(parent) = producers
+                .get(&input.prev_transaction_output_hash)
+                .and_then(|parent_txid| by_txid.get(parent_txid))
+            {
+                visit(parent, by_txid, producers, visited, ordered);
+            }
+        }
+        ordered.push(transaction);
+    }
+
+    for transaction in candidates {
+        visit(transaction, &by_txid, producers, &mut visited, &mut ordered);
+    }
+    ordered
+}
+
+// Builds candidate blocks out of the mempool, ready for a miner to search for
+// a valid nonce.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockAssembler {
+    pub max_block_size: u64,
+    pub block_subsidy: u64,
+}
+
+impl BlockAssembler {
+    pub fn new(max_block_size: u64, block_subsidy: u64) -> Self {
+        Self {
+            max_block_size,
+            block_subsidy,
+        }
+    }
+
+    // Greedily pack transactions sorted by descending fee rate (fee /
+    // serialized byte) into a block under `max_block_size`, then prepend a
+    // coinbase paying the subsidy plus the fees collected from everything
+    // selected.
+    pub fn assemble_block(
+        &self,
+        mempool: &Mempool,
+        utxos: &HashMap<Txid, TransactionOutput>,
+        prev_block_hash: BlockHash,
+        target: U256,
+        coinbase_pubkey: PublicKey,
+    ) -> Block {
+        // Fees have to be priced against the utxo set plus every other
+        // mempool output, not just the utxo set, or a transaction spending
+        // another mempool transaction's output looks like it pays a 0 fee.
+        let lookup = mempool.lookup(utxos);
+        let mut candidates: Vec<&Transaction> = mempool.transactions().collect();
+        candidates.sort_by(|a, b| {
+            fee_rate(b, &lookup)
+                .partial_cmp(&fee_rate(a, &lookup))
+                .unwrap()
+        });
+
+        // Sorting by fee rate alone can put a chained spend ahead of the
+        // mempool transaction whose output it spends; reorder so every
+        // parent comes before its children.
+        let producers = producers(&candidates);
+        let ordered = order_parents_first(&candidates, &producers);
+
+        let mut selected = vec![];
+        let mut selected_txids: HashSet<Txid> = HashSet::new();
+        let mut size = 0u64;
+        let mut fees = 0u64;
+        for transaction in ordered {
+            // A chained spend can't be included without the parent it
+            // spends from also landing in this block.
+            let missing_parent = transaction.inputs.iter().any(|input| {
+                producers
+                    .get(&input.prev_transaction_output_hash)
+                    .is_some_and(|parent| !selected_txids.contains(parent))
+            });
+            if missing_parent {
+                continue;
+            }
+            let transaction_size = serialized_len(transaction);
+            if size + transaction_size > self.max_block_size {
+                continue;
+            }
+            size += transaction_size;
+            fees += transaction_fee(transaction, &lookup);
+            selected_txids.insert(transaction.hash());
+            selected.push(transaction.clone());
+        }
+
+        let coinbase = Transaction::new(
+            vec![],
+            vec![TransactionOutput {
+                value: self.block_subsidy + fees,
+                unique_id: Uuid::new_v4(),
+                script_pubkey: p2pkh_script_pubkey(&coinbase_pubkey),
+            }],
+        );
+
+        let mut transactions = vec![coinbase];
+        transactions.extend(selected);
+        let merkle_root = MerkleRoot::calculate(&transactions);
+
+        let header = BlockHeader::new(Utc::now(), 0, prev_block_hash, merkle_root, target);
+        Block::new(header, transactions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{PrivateKey, Signature};
+    use crate::network::Network;
+    use crate::script::{p2pkh_script_pubkey, p2pkh_script_sig};
+    use crate::types::{Blockchain, TransactionInput};
+
+    #[test]
+    fn assembled_block_with_only_a_coinbase_is_accepted() {
+        // Regtest's target is U256::max_value(), so any nonce satisfies it
+        // and this exercises assemble_block + add_block without mining.
+        let mut blockchain = Blockchain::new(Network::Regtest);
+        let mempool = Mempool::new();
+        let assembler = BlockAssembler::new(1_000_000, blockchain.network.params().block_subsidy);
+        let prev_block_hash = blockchain.blocks.last().unwrap().hash();
+        let target = blockchain.blocks[0].header.target;
+        let coinbase_pubkey = PrivateKey::new_key().public_key();
+
+        let block = assembler.assemble_block(
+            &mempool,
+            &blockchain.utxos,
+            prev_block_hash,
+            target,
+            coinbase_pubkey,
+        );
+
+        assert!(blockchain.add_block(block).is_ok());
+    }
+
+    fn spend(
+        funding_key: &PrivateKey,
+        funding_output: &TransactionOutput,
+        to_key: &PrivateKey,
+        value: u64,
+    ) -> Transaction {
+        let prev_output_hash = funding_output.hash();
+        let signature = Signature::sign_output(&prev_output_hash, funding_key);
+        let script_sig = p2pkh_script_sig(&signature, &funding_key.public_key());
+        let input = TransactionInput::new(prev_output_hash, script_sig);
+        let output = TransactionOutput {
+            value,
+            unique_id: Uuid::new_v4(),
+            script_pubkey: p2pkh_script_pubkey(&to_key.public_key()),
+        };
+        Transaction::new(vec![input], vec![output])
+    }
+
+    #[test]
+    fn assembled_block_accepts_a_chained_mempool_spend() {
+        let mut blockchain = Blockchain::new(Network::Regtest);
+        let mempool_funding_key = PrivateKey::new_key();
+        let funding_output = TransactionOutput {
+            value: 100,
+            unique_id: Uuid::new_v4(),
+            script_pubkey: p2pkh_script_pubkey(&mempool_funding_key.public_key()),
+        };
+        blockchain
+            .utxos
+            .insert(funding_output.hash(), funding_output.clone());
+
+        let middle_key = PrivateKey::new_key();
+        let final_key = PrivateKey::new_key();
+        // tx1 spends the pre-existing utxo; tx2 spends tx1's own output,
+        // which only exists in the mempool until this block confirms it.
+        let tx1 = spend(&mempool_funding_key, &funding_output, &middle_key, 90);
+        let tx2 = spend(&middle_key, &tx1.outputs[0], &final_key, 80);
+
+        let mut mempool = Mempool::new();
+        mempool.add_transaction(tx1, &blockchain.utxos).unwrap();
+        mempool.add_transaction(tx2, &blockchain.utxos).unwrap();
+
+        let assembler = BlockAssembler::new(1_000_000, blockchain.network.params().block_subsidy);
+        let prev_block_hash = blockchain.blocks.last().unwrap().hash();
+        let target = blockchain.blocks[0].header.target;
+        let coinbase_pubkey = PrivateKey::new_key().public_key();
+
+        let block = assembler.assemble_block(
+            &mempool,
+            &blockchain.utxos,
+            prev_block_hash,
+            target,
+            coinbase_pubkey,
+        );
+
+        assert!(blockchain.add_block(block).is_ok());
+    }
+}
@@ -0,0 +1,104 @@
+/*
+Which chain a node is participating in. Each network carries its own genesis
+block and consensus parameters, so the same Blockchain code can run a public
+chain or a disposable local one just by swapping this value in at
+construction.
+*/
+
+use crate::crypto::PrivateKey;
+use crate::pow::max_target as mainnet_max_target;
+use crate::script::p2pkh_script_pubkey;
+use crate::sha256::BlockHash;
+use crate::types::{Block, BlockHeader, Transaction, TransactionOutput};
+use crate::util::MerkleRoot;
+use crate::U256;
+use chrono::{TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+// Consensus parameters that vary per network.
+#[derive(Debug, Clone, Copy)]
+pub struct Params {
+    pub max_target: U256,
+    pub block_subsidy: u64,
+}
+
+impl Network {
+    pub fn params(&self) -> Params {
+        match self {
+            // Mainnet starts at Bitcoin's historical difficulty-1 target.
+            Network::Mainnet => Params {
+                max_target: mainnet_max_target(),
+                block_subsidy: 50_0000_0000,
+            },
+            // Testnet and signet use the same easiest target as mainnet but
+            // are expected to be reset and restarted freely.
+            Network::Testnet => Params {
+                max_target: mainnet_max_target(),
+                block_subsidy: 50_0000_0000,
+            },
+            Network::Signet => Params {
+                max_target: mainnet_max_target(),
+                block_subsidy: 50_0000_0000,
+            },
+            // Regtest has no real miners behind it, so any hash at all is
+            // allowed to pass, letting a single node mine blocks instantly.
+            Network::Regtest => Params {
+                max_target: U256::max_value(),
+                block_subsidy: 50_0000_0000,
+            },
+        }
+    }
+
+    // A version byte for Base58Check pubkey-hash addresses on this network,
+    // matching the prefixes real Bitcoin nodes use.
+    pub fn pubkey_hash_version(&self) -> u8 {
+        match self {
+            Network::Mainnet => 0x00,
+            Network::Testnet | Network::Signet | Network::Regtest => 0x6f,
+        }
+    }
+
+    // The network's hardcoded first block. Its coinbase pays a fixed,
+    // reproducible key rather than anyone's real wallet.
+    pub fn genesis_block(&self) -> Block {
+        let coinbase_key = PrivateKey::from_bytes(&genesis_key_seed(*self)).public_key();
+        let coinbase = Transaction::new(
+            vec![],
+            vec![TransactionOutput {
+                value: self.params().block_subsidy,
+                unique_id: Uuid::nil(),
+                script_pubkey: p2pkh_script_pubkey(&coinbase_key),
+            }],
+        );
+        let transactions = vec![coinbase];
+        let merkle_root = MerkleRoot::calculate(&transactions);
+        let header = BlockHeader::new(
+            Utc.timestamp_opt(0, 0).unwrap(),
+            0,
+            BlockHash::zero(),
+            merkle_root,
+            self.params().max_target,
+        );
+        Block::new(header, transactions)
+    }
+}
+
+fn genesis_key_seed(network: Network) -> [u8; 32] {
+    let mut seed = [0u8; 32];
+    seed[31] = match network {
+        Network::Mainnet => 1,
+        Network::Testnet => 2,
+        Network::Signet => 3,
+        Network::Regtest => 4,
+    };
+    seed
+}
@@ -0,0 +1,168 @@
+/*
+Proof-of-work difficulty retargeting, modeled on Bitcoin's.
+
+Every DIFFCHANGE_INTERVAL blocks, the network recomputes the target based on
+how long the previous interval actually took to mine compared to
+TARGET_TIMESPAN (the ideal time). Miners then have to find a hash at or
+below the new target, which keeps average block times roughly constant even
+as total network hash power changes.
+*/
+
+use crate::U256;
+use serde::{Deserialize, Serialize};
+
+/// Number of blocks between difficulty retargets (Bitcoin retargets every 2016 blocks).
+pub const DIFFCHANGE_INTERVAL: u64 = 2016;
+/// Ideal wall-clock time, in seconds, to mine DIFFCHANGE_INTERVAL blocks (two weeks).
+pub const TARGET_TIMESPAN: i64 = 14 * 24 * 60 * 60;
+
+/// The easiest allowed target: Bitcoin's historical difficulty-1 target,
+/// 0x00000000FFFF0000000000000000000000000000000000000000000000000000.
+/// No target may ever be retargeted above this.
+pub fn max_target() -> U256 {
+    U256::from(0xffffu64) << 208
+}
+
+/// Clamp `actual_timespan` to [TARGET_TIMESPAN/4, TARGET_TIMESPAN*4] so a single
+/// interval can't swing the difficulty by more than 4x in either direction.
+fn clamp_timespan(actual_timespan: i64) -> i64 {
+    actual_timespan.clamp(TARGET_TIMESPAN / 4, TARGET_TIMESPAN * 4)
+}
+
+/// Compute the new target for the next interval given the old target, how
+/// long the last interval actually took to mine, and the network's easiest
+/// allowed target.
+pub fn calculate_new_target(old_target: U256, actual_timespan: i64, max_target: U256) -> U256 {
+    let actual_timespan = clamp_timespan(actual_timespan);
+    let actual_timespan = U256::from(actual_timespan as u64);
+    let target_timespan = U256::from(TARGET_TIMESPAN as u64);
+
+    // old_target * actual_timespan can overflow a U256 before the division
+    // brings it back down, e.g. for Regtest's max_target of U256::max_value().
+    // Splitting old_target into quotient and remainder by target_timespan
+    // gives the same floor(old_target * actual_timespan / target_timespan)
+    // without the oversized intermediate product. If the result itself
+    // doesn't fit in a U256 it is certainly above max_target, so saturating
+    // straight to it on overflow is exact, not just a fallback.
+    let quotient = old_target / target_timespan;
+    let remainder = old_target % target_timespan;
+    match quotient
+        .checked_mul(actual_timespan)
+        .and_then(|scaled| scaled.checked_add(remainder * actual_timespan / target_timespan))
+    {
+        Some(new_target) => new_target.min(max_target),
+        None => max_target,
+    }
+}
+
+/// A Bitcoin-style "compact" (nBits) encoding of a 256-bit target: a 1-byte
+/// exponent and a 3-byte mantissa, packed into 4 bytes. This lets a target be
+/// stored compactly in a header instead of as a full U256.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Compact(u32);
+
+impl Compact {
+    pub fn from_u256(target: U256) -> Self {
+        let mut bytes = [0u8; 32];
+        target.to_big_endian(&mut bytes);
+
+        let start = match bytes.iter().position(|&b| b != 0) {
+            Some(index) => index,
+            None => return Compact(0),
+        };
+        let mut size = (32 - start) as u32;
+        let mut mantissa = if size <= 3 {
+            let mut mantissa = 0u32;
+            for &byte in &bytes[start..32] {
+                mantissa = (mantissa << 8) | byte as u32;
+            }
+            mantissa << (8 * (3 - size))
+        } else {
+            ((bytes[start] as u32) << 16) | ((bytes[start + 1] as u32) << 8) | (bytes[start + 2] as u32)
+        };
+
+        // The top bit of the mantissa is reserved as a sign bit; if it would
+        // be set, shift the mantissa down a byte and bump the exponent.
+        if mantissa & 0x0080_0000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+
+        Compact((size << 24) | mantissa)
+    }
+
+    pub fn to_u256(self) -> U256 {
+        let size = self.0 >> 24;
+        let mantissa = U256::from(self.0 & 0x007f_ffff);
+        if size <= 3 {
+            mantissa >> (8 * (3 - size))
+        } else {
+            mantissa << (8 * (size - 3))
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; 4] {
+        self.0.to_be_bytes()
+    }
+
+    pub fn from_bytes(bytes: [u8; 4]) -> Self {
+        Compact(u32::from_be_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_round_trips_max_target() {
+        let target = max_target();
+        let compact = Compact::from_u256(target);
+        assert_eq!(compact.to_u256(), target);
+    }
+
+    #[test]
+    fn compact_round_trips_small_target() {
+        // Compact only keeps a 3-byte mantissa, so only values with at most
+        // 3 significant bytes survive from_u256 -> to_u256 exactly.
+        let target = U256::from(0x0012_3456u64);
+        let compact = Compact::from_u256(target);
+        assert_eq!(compact.to_u256(), target);
+    }
+
+    #[test]
+    fn compact_bytes_round_trip() {
+        let compact = Compact::from_u256(max_target());
+        assert_eq!(Compact::from_bytes(compact.to_bytes()), compact);
+    }
+
+    #[test]
+    fn calculate_new_target_halves_when_interval_is_twice_as_fast() {
+        let old_target = max_target() >> 1;
+        let new_target = calculate_new_target(old_target, TARGET_TIMESPAN / 2, max_target());
+        assert_eq!(new_target, old_target / 2);
+    }
+
+    #[test]
+    fn calculate_new_target_never_exceeds_max_target() {
+        let new_target = calculate_new_target(max_target(), TARGET_TIMESPAN * 4, max_target());
+        assert_eq!(new_target, max_target());
+    }
+
+    #[test]
+    fn calculate_new_target_does_not_overflow_near_u256_max() {
+        // Regtest's max_target is U256::max_value(), so old_target * actual_timespan
+        // would overflow a U256 before the division brings it back into range.
+        let old_target = U256::max_value();
+        let new_target = calculate_new_target(old_target, TARGET_TIMESPAN * 4, old_target);
+        assert_eq!(new_target, old_target);
+    }
+
+    #[test]
+    fn timespan_is_clamped_to_a_4x_swing() {
+        let old_target = max_target() >> 4;
+        let unclamped = calculate_new_target(old_target, TARGET_TIMESPAN * 100, max_target());
+        let clamped = calculate_new_target(old_target, TARGET_TIMESPAN * 4, max_target());
+        assert_eq!(unclamped, clamped);
+    }
+}
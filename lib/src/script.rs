@@ -0,0 +1,296 @@
+/*
+A minimal, Bitcoin-style scripting layer. `TransactionOutput::script_pubkey`
+and `TransactionInput::script_sig` are opaque byte strings interpreted by a
+tiny stack machine instead of a bare public key and signature, which is what
+lets a spending policy other than "sign with this one key" exist later.
+Today the only policy built on top of it is pay-to-pubkey-hash (P2PKH).
+*/
+
+use crate::crypto::{PublicKey, Signature};
+use crate::error::{BtcError, Result};
+use crate::network::Network;
+use crate::sha256::{sha256d_bytes, Txid};
+use crate::types::{Transaction, TransactionInput, SEQUENCE_FINAL};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use ripemd::Ripemd160;
+
+pub const OP_PUSHDATA1: u8 = 0x4c;
+pub const OP_DUP: u8 = 0x76;
+pub const OP_HASH160: u8 = 0xa9;
+pub const OP_EQUALVERIFY: u8 = 0x88;
+pub const OP_CHECKSIG: u8 = 0xac;
+pub const OP_CHECKLOCKTIMEVERIFY: u8 = 0xb1;
+
+type Stack = Vec<Vec<u8>>;
+
+// RIPEMD160(SHA256(data)), the hash P2PKH addresses and scripts are built
+// around.
+pub fn hash160(data: &[u8]) -> [u8; 20] {
+    let sha256_digest = Sha256::digest(data);
+    Ripemd160::digest(sha256_digest).into()
+}
+
+// Build the standard P2PKH locking script for `pubkey`:
+// OP_DUP OP_HASH160 <pubkey hash> OP_EQUALVERIFY OP_CHECKSIG
+pub fn p2pkh_script_pubkey(pubkey: &PublicKey) -> Vec<u8> {
+    let pubkey_hash = hash160(&serialize(pubkey));
+    let mut script = Vec::with_capacity(5 + pubkey_hash.len());
+    script.push(OP_DUP);
+    script.push(OP_HASH160);
+    push_data(&mut script, &pubkey_hash);
+    script.push(OP_EQUALVERIFY);
+    script.push(OP_CHECKSIG);
+    script
+}
+
+// Build the unlocking script for a P2PKH spend: <signature> <pubkey>
+pub fn p2pkh_script_sig(signature: &Signature, pubkey: &PublicKey) -> Vec<u8> {
+    let mut script = vec![];
+    push_data(&mut script, &serialize(signature));
+    push_data(&mut script, &serialize(pubkey));
+    script
+}
+
+// Base58Check-encode a pubkey hash as an address for `network`: version byte
+// + hash + first 4 bytes of the double-sha256 checksum.
+pub fn encode_address(network: Network, pubkey_hash: &[u8; 20]) -> String {
+    let mut payload = Vec::with_capacity(1 + pubkey_hash.len() + 4);
+    payload.push(network.pubkey_hash_version());
+    payload.extend_from_slice(pubkey_hash);
+    payload.extend_from_slice(&sha256d_bytes(&payload)[..4]);
+    bs58::encode(payload).into_string()
+}
+
+// Decode and checksum-verify a Base58Check address, returning the network it
+// was minted for and the pubkey hash it pays.
+//
+// Testnet, Signet and Regtest all share version byte 0x6f (see
+// Network::pubkey_hash_version), so an address minted on Signet or Regtest
+// decodes back as Testnet here; the three are not distinguishable from the
+// address alone.
+pub fn decode_address(address: &str) -> Result<(Network, [u8; 20])> {
+    let payload = bs58::decode(address)
+        .into_vec()
+        .map_err(|_| BtcError::InvalidAddress)?;
+    if payload.len() != 25 {
+        return Err(BtcError::InvalidAddress);
+    }
+    let (data, checksum) = payload.split_at(21);
+    if checksum != &sha256d_bytes(data)[..4] {
+        return Err(BtcError::InvalidAddress);
+    }
+    let network = match data[0] {
+        0x00 => Network::Mainnet,
+        0x6f => Network::Testnet,
+        _ => return Err(BtcError::InvalidAddress),
+    };
+    let mut pubkey_hash = [0u8; 20];
+    pubkey_hash.copy_from_slice(&data[1..]);
+    Ok((network, pubkey_hash))
+}
+
+// Run `script_sig` followed by `script_pubkey` over a shared stack and
+// report whether the top of the stack is left truthy, the way Bitcoin
+// validates a spend.
+pub fn verify_script(
+    script_sig: &[u8],
+    script_pubkey: &[u8],
+    spent_output_hash: &Txid,
+    transaction: &Transaction,
+    input: &TransactionInput,
+) -> bool {
+    let mut stack: Stack = vec![];
+    run(script_sig, &mut stack, spent_output_hash, transaction, input)
+        && run(script_pubkey, &mut stack, spent_output_hash, transaction, input)
+        && matches!(stack.last(), Some(top) if is_truthy(top))
+}
+
+fn is_truthy(value: &[u8]) -> bool {
+    value.iter().any(|&byte| byte != 0)
+}
+
+// Push `data` onto the script, using a single-byte length prefix for data up
+// to 75 bytes (as Bitcoin's standard push opcodes do) and falling back to
+// OP_PUSHDATA1 for anything larger, up to 255 bytes.
+fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    match data.len() {
+        len @ 1..=75 => script.push(len as u8),
+        len @ 76..=255 => {
+            script.push(OP_PUSHDATA1);
+            script.push(len as u8);
+        }
+        _ => panic!("script push too large"),
+    }
+    script.extend_from_slice(data);
+}
+
+fn serialize<T: Serialize>(value: &T) -> Vec<u8> {
+    let mut bytes = vec![];
+    ciborium::into_writer(value, &mut bytes).expect("failed to serialize script data");
+    bytes
+}
+
+fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Option<T> {
+    ciborium::de::from_reader(bytes).ok()
+}
+
+// Interpret `script` against `stack`, returning false the moment any opcode
+// fails or the script is malformed.
+fn run(
+    script: &[u8],
+    stack: &mut Stack,
+    spent_output_hash: &Txid,
+    transaction: &Transaction,
+    input: &TransactionInput,
+) -> bool {
+    let mut pos = 0;
+    while pos < script.len() {
+        let opcode = script[pos];
+        pos += 1;
+        match opcode {
+            // a byte in 1..=75 is a push of that many bytes of data
+            len @ 1..=75 => {
+                let len = len as usize;
+                if pos + len > script.len() {
+                    return false;
+                }
+                stack.push(script[pos..pos + len].to_vec());
+                pos += len;
+            }
+            OP_PUSHDATA1 => {
+                let Some(&len) = script.get(pos) else {
+                    return false;
+                };
+                let len = len as usize;
+                pos += 1;
+                if pos + len > script.len() {
+                    return false;
+                }
+                stack.push(script[pos..pos + len].to_vec());
+                pos += len;
+            }
+            OP_DUP => match stack.last().cloned() {
+                Some(top) => stack.push(top),
+                None => return false,
+            },
+            OP_HASH160 => match stack.pop() {
+                Some(top) => stack.push(hash160(&top).to_vec()),
+                None => return false,
+            },
+            OP_EQUALVERIFY => match (stack.pop(), stack.pop()) {
+                (Some(a), Some(b)) if a == b => {}
+                _ => return false,
+            },
+            OP_CHECKSIG => {
+                let (Some(pubkey_bytes), Some(signature_bytes)) = (stack.pop(), stack.pop()) else {
+                    return false;
+                };
+                let (Some(public_key), Some(signature)) = (
+                    deserialize::<PublicKey>(&pubkey_bytes),
+                    deserialize::<Signature>(&signature_bytes),
+                ) else {
+                    return false;
+                };
+                let is_valid = signature.verify(spent_output_hash, &public_key);
+                stack.push(vec![is_valid as u8]);
+            }
+            OP_CHECKLOCKTIMEVERIFY => {
+                let Some(top) = stack.last() else {
+                    return false;
+                };
+                let Ok(locktime_bytes) = <[u8; 4]>::try_from(top.as_slice()) else {
+                    return false;
+                };
+                let locktime = u32::from_le_bytes(locktime_bytes);
+                if input.sequence == SEQUENCE_FINAL || locktime > transaction.lock_time {
+                    return false;
+                }
+                // OP_CHECKLOCKTIMEVERIFY leaves its argument on the stack
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::PrivateKey;
+    use crate::types::{Transaction, TransactionInput, TransactionOutput};
+    use uuid::Uuid;
+
+    fn spendable_output(script_pubkey: Vec<u8>) -> TransactionOutput {
+        TransactionOutput {
+            value: 50,
+            unique_id: Uuid::new_v4(),
+            script_pubkey,
+        }
+    }
+
+    #[test]
+    fn p2pkh_spend_with_correct_key_verifies() {
+        let private_key = PrivateKey::new_key();
+        let public_key = private_key.public_key();
+        let script_pubkey = p2pkh_script_pubkey(&public_key);
+        let prev_output = spendable_output(script_pubkey.clone());
+        let prev_output_hash = prev_output.hash();
+
+        let signature = Signature::sign_output(&prev_output_hash, &private_key);
+        let script_sig = p2pkh_script_sig(&signature, &public_key);
+        let input = TransactionInput::new(prev_output_hash, script_sig.clone());
+        let transaction = Transaction::new(vec![input.clone()], vec![]);
+
+        assert!(verify_script(
+            &script_sig,
+            &script_pubkey,
+            &prev_output_hash,
+            &transaction,
+            &input,
+        ));
+    }
+
+    #[test]
+    fn p2pkh_spend_with_wrong_key_fails() {
+        let private_key = PrivateKey::new_key();
+        let public_key = private_key.public_key();
+        let script_pubkey = p2pkh_script_pubkey(&public_key);
+        let prev_output = spendable_output(script_pubkey.clone());
+        let prev_output_hash = prev_output.hash();
+
+        let wrong_key = PrivateKey::new_key();
+        let signature = Signature::sign_output(&prev_output_hash, &wrong_key);
+        let script_sig = p2pkh_script_sig(&signature, &wrong_key.public_key());
+        let input = TransactionInput::new(prev_output_hash, script_sig.clone());
+        let transaction = Transaction::new(vec![input.clone()], vec![]);
+
+        assert!(!verify_script(
+            &script_sig,
+            &script_pubkey,
+            &prev_output_hash,
+            &transaction,
+            &input,
+        ));
+    }
+
+    #[test]
+    fn address_round_trips_through_encode_decode() {
+        let private_key = PrivateKey::new_key();
+        let pubkey_hash = hash160(&serialize(&private_key.public_key()));
+        let address = encode_address(Network::Mainnet, &pubkey_hash);
+        assert_eq!(
+            decode_address(&address).unwrap(),
+            (Network::Mainnet, pubkey_hash)
+        );
+    }
+
+    #[test]
+    fn decode_address_rejects_bad_checksum() {
+        let private_key = PrivateKey::new_key();
+        let pubkey_hash = hash160(&serialize(&private_key.public_key()));
+        let mut address = encode_address(Network::Mainnet, &pubkey_hash);
+        address.push('1');
+        assert!(decode_address(&address).is_err());
+    }
+}
@@ -1,56 +1,115 @@
+/*
+Bitcoin hashes block headers and transactions with SHA256 applied twice
+(sha256d) rather than once, which guards against length-extension attacks
+on a single SHA256 round.
+
+Rather than a single opaque `Hash` type shared by every entity, hashes are
+typed by what they identify: a `Txid` can't be mistaken for a `BlockHash`,
+and a `TxMerkleNode` can't be mistaken for either, even though all three are
+the same 32 bytes under the hood.
+*/
+
 use crate::U256;
 use serde::{Deserialize, Serialize};
 use sha256::digest;
 use std::fmt;
 
-#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
-pub struct Hash(U256);
-
-impl Hash {
-    // hash anything that can be serde Serialized via ciborium
-    pub fn hash<T: serde::Serialize>(data: &T) -> Self {
-        let mut serialized: Vec<u8> = vec![];
-        if let Err(e) = ciborium::into_writer(data, &mut serialized) {
-            panic!("Failed to serialize data: {:?}.", e)
-        };
-
-        let hash = digest(&serialized);
-        // hash is a string containing the hexadecimal representation of a hash.
-        // we take this hex string and convert it into a vector.
-        let hash_bytes = hex::decode(hash).unwrap();
-        let hash_array: [u8; 32] = hash_bytes.as_slice().try_into().unwrap();
-        Hash(U256::from(hash_array))
-        /*
-        check https://github.com/braiins/build-bitcoin-in-rust/issues/7 for latest uint vesrion bug.
-        // Convert the 32-byte array into a U256 using from_big_endian
-        let mut u256_value = U256::zero();
-        u256_value = U256::from_big_endian(&hash_array);
-
-        Hash(u256_value)
-        */
-    }
+fn sha256d(bytes: &[u8]) -> [u8; 32] {
+    let first = hex::decode(digest(bytes)).unwrap();
+    let second = hex::decode(digest(&first)).unwrap();
+    second.as_slice().try_into().unwrap()
+}
 
-    // check if a hash matches a target (for POW)
-    pub fn matches_target(&self, target: U256) -> bool {
-        self.0 <= target
-    }
+// Double-SHA256 of raw bytes, exposed for callers that need it directly
+// (e.g. Base58Check address checksums) rather than via a typed hash.
+pub fn sha256d_bytes(bytes: &[u8]) -> [u8; 32] {
+    sha256d(bytes)
+}
 
-    // zero hash
-    pub fn zero() -> Self {
-        Hash(U256::zero())
-    }
+// hash anything that can be serde Serialized via ciborium
+fn hash_data<T: Serialize>(data: &T) -> U256 {
+    let mut serialized: Vec<u8> = vec![];
+    if let Err(e) = ciborium::into_writer(data, &mut serialized) {
+        panic!("Failed to serialize data: {:?}.", e)
+    };
+    U256::from(sha256d(&serialized))
+    /*
+    check https://github.com/braiins/build-bitcoin-in-rust/issues/7 for latest uint vesrion bug.
+    // Convert the 32-byte array into a U256 using from_big_endian
+    let mut u256_value = U256::zero();
+    u256_value = U256::from_big_endian(&hash_array);
+
+    Hash(u256_value)
+    */
+}
 
-    // convert to bytes
-    pub fn as_bytes(&self) -> [u8; 32] {
-        let mut bytes: Vec<u8> = vec![0; 32];
-        // the convention is typically little-endian. Bitcoin specifically uses little-endian for hashing and serialization.
-        self.0.to_little_endian(&mut bytes);
-        bytes.as_slice().try_into().unwrap()
+// Hash data the old, pre-sha256d way (single SHA256 round). Kept around so
+// data hashed before the sha256d switch can still be looked up or
+// re-verified during a migration; new code should always go through the
+// `Txid`/`BlockHash`/`TxMerkleNode` constructors instead.
+pub fn legacy_single_sha256<T: Serialize>(data: &T) -> U256 {
+    let mut serialized: Vec<u8> = vec![];
+    if let Err(e) = ciborium::into_writer(data, &mut serialized) {
+        panic!("Failed to serialize data: {:?}.", e)
+    };
+    let hash = digest(&serialized);
+    let hash_bytes = hex::decode(hash).unwrap();
+    let hash_array: [u8; 32] = hash_bytes.as_slice().try_into().unwrap();
+    U256::from(hash_array)
+}
+
+macro_rules! hash_newtype {
+    ($name:ident) => {
+        #[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+        pub struct $name(U256);
+
+        impl $name {
+            // hash anything that can be serde Serialized via ciborium
+            pub fn hash<T: Serialize>(data: &T) -> Self {
+                $name(hash_data(data))
+            }
+
+            // zero hash
+            pub fn zero() -> Self {
+                $name(U256::zero())
+            }
+
+            // convert to bytes
+            pub fn as_bytes(&self) -> [u8; 32] {
+                let mut bytes: Vec<u8> = vec![0; 32];
+                // the convention is typically little-endian. Bitcoin specifically uses little-endian for hashing and serialization.
+                self.0.to_little_endian(&mut bytes);
+                bytes.as_slice().try_into().unwrap()
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{:x}", self.0)
+            }
+        }
+    };
+}
+
+// Hashes a transaction (or a transaction output, which needs the same
+// "unique reference" property a txid gives us).
+hash_newtype!(Txid);
+// Hashes a block header.
+hash_newtype!(BlockHash);
+// A node in a block's transaction merkle tree, including its root.
+hash_newtype!(TxMerkleNode);
+
+impl BlockHash {
+    // check if a block hash matches a target (for POW)
+    pub fn matches_target(&self, target: U256) -> bool {
+        self.0 <= target
     }
 }
 
-impl fmt::Display for Hash {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:x}", self.0)
+// A txid can be reinterpreted as a merkle tree leaf without hashing it
+// again, exactly as it sits in the finished transaction.
+impl From<Txid> for TxMerkleNode {
+    fn from(txid: Txid) -> Self {
+        TxMerkleNode(txid.0)
     }
 }
@@ -1,6 +1,8 @@
-use crate::crypto::{PublicKey, Signature};
 use crate::error::{BtcError, Result};
-use crate::sha256::Hash;
+use crate::network::Network;
+use crate::pow::{calculate_new_target, DIFFCHANGE_INTERVAL};
+use crate::script;
+use crate::sha256::{BlockHash, Txid};
 use crate::util::MerkleRoot;
 use crate::U256;
 use chrono::{DateTime, Utc};
@@ -13,53 +15,94 @@ use uuid::Uuid;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Blockchain {
-    pub utxos: HashMap<Hash, TransactionOutput>,
+    pub network: Network,
+    pub utxos: HashMap<Txid, TransactionOutput>,
     pub blocks: Vec<Block>,
 }
 
 impl Blockchain {
-    pub fn new() -> Self {
+    // Seed the chain with `network`'s genesis block, so there is no empty
+    // chain state to special-case later on.
+    pub fn new(network: Network) -> Self {
+        let genesis = network.genesis_block();
+        let mut utxos = HashMap::new();
+        for transaction in &genesis.transactions {
+            for output in &transaction.outputs {
+                utxos.insert(output.hash(), output.clone());
+            }
+        }
         Self {
-            utxos: HashMap::new(),
-            blocks: vec![],
+            network,
+            utxos,
+            blocks: vec![genesis],
         }
     }
 
     pub fn add_block(&mut self, block: Block) -> Result<()> {
-        // check if the block is valid
-        if self.blocks.is_empty() {
-            // if this is the first block, check if the block's
-            // previous hash is all zeros.
-            if block.header.prev_block_hash != Hash::zero() {
-                return Err(BtcError::InvalidBlock);
-            } else {
-                // check if the block's previous hash is the
-                // hash of the last block
-                let last_block = self.blocks.last().unwrap();
-                if block.header.prev_block_hash != last_block.hash() {
-                    return Err(BtcError::InvalidBlock);
-                }
-                // check if the block's hash is less than the target
-                if !block.header.hash().matches_target(block.header.target) {
-                    return Err(BtcError::InvalidBlock);
-                }
-                // check if the block's merkle root is correct
-                let calculated_merkle_root = MerkleRoot::calculate(&block.transactions);
-                if calculated_merkle_root != block.header.merkle_root {
-                    return Err(BtcError::InvalidMerkleRoot);
-                }
-                // check if the block's timestam is after the last block's timestamp
-                if block.header.timestamp <= last_block.header.timestamp {
-                    return Err(BtcError::InvalidBlock);
-                }
-                // Verify that all trasactions in the block are valid
-                unimplemented!();
-            }
+        // check if the block's previous hash is the hash of the last block
+        let last_block = self.blocks.last().expect("genesis block is always present");
+        if block.header.prev_block_hash != last_block.hash() {
+            return Err(BtcError::InvalidBlock);
+        }
+        // check if the block's hash is at or below the target
+        if !block.header.hash().matches_target(block.header.target) {
+            return Err(BtcError::InvalidBlock);
         }
+        // check if the block's merkle root is correct
+        let calculated_merkle_root = MerkleRoot::calculate(&block.transactions);
+        if calculated_merkle_root != block.header.merkle_root {
+            return Err(BtcError::InvalidMerkleRoot);
+        }
+        // check if the block's timestam is after the last block's timestamp
+        if block.header.timestamp <= last_block.header.timestamp {
+            return Err(BtcError::InvalidBlock);
+        }
+        // check if the block's target matches what difficulty retargeting expects
+        if block.header.target != self.expected_target() {
+            return Err(BtcError::InvalidBlock);
+        }
+        // Verify that all trasactions in the block are valid. The block being
+        // added will sit at height self.blocks.len() once pushed.
+        let height = self.blocks.len() as u64;
+        block.verify_transactions(
+            height,
+            self.median_time_past(),
+            self.network.params().block_subsidy,
+            &self.utxos,
+        )?;
         self.blocks.push(block);
+        self.rebuild_utxos();
         Ok(())
     }
 
+    // Median of the last 11 blocks' timestamps, used to evaluate
+    // timestamp-style transaction lock times the same way Bitcoin does.
+    fn median_time_past(&self) -> DateTime<Utc> {
+        const MEDIAN_TIME_SPAN: usize = 11;
+        let start = self.blocks.len().saturating_sub(MEDIAN_TIME_SPAN);
+        let mut timestamps: Vec<DateTime<Utc>> =
+            self.blocks[start..].iter().map(|b| b.header.timestamp).collect();
+        timestamps.sort();
+        timestamps[timestamps.len() / 2]
+    }
+
+    // Work out the target the next block must meet, retargeting difficulty
+    // every DIFFCHANGE_INTERVAL blocks based on how long that interval took.
+    fn expected_target(&self) -> U256 {
+        let last_block = self.blocks.last().unwrap();
+        if !(self.blocks.len() as u64).is_multiple_of(DIFFCHANGE_INTERVAL) {
+            return last_block.header.target;
+        }
+        let interval_start = &self.blocks[self.blocks.len() - DIFFCHANGE_INTERVAL as usize];
+        let actual_timespan = (last_block.header.timestamp - interval_start.header.timestamp)
+            .num_seconds();
+        calculate_new_target(
+            last_block.header.target,
+            actual_timespan,
+            self.network.params().max_target,
+        )
+    }
+
     // Rebuild UTXO set from the blockchain
     pub fn rebuild_utxos(&mut self) {
         for block in &self.blocks {
@@ -89,60 +132,145 @@ impl Block {
             transactions,
         }
     }
-    pub fn hash(&self) -> Hash {
-        Hash::hash(self)
+    pub fn hash(&self) -> BlockHash {
+        BlockHash::hash(self)
     }
 
     // verify all transactions in the block
-    pub fn verify_transactions(&self, utxos: &HashMap<Hash, TransactionOutput>) -> Result<()> {
-        if self.transactions.is_empty() {
+    pub fn verify_transactions(
+        &self,
+        height: u64,
+        median_time_past: DateTime<Utc>,
+        block_subsidy: u64,
+        utxos: &HashMap<Txid, TransactionOutput>,
+    ) -> Result<()> {
+        // The first transaction must be the coinbase: it has no inputs, so it
+        // can't be run through verify_transaction like the rest (there is
+        // nothing to check a script or input value against). Instead its
+        // total output is capped once the real transactions' fees are known.
+        let Some((coinbase, rest)) = self.transactions.split_first() else {
+            return Err(BtcError::InvalidTransaction);
+        };
+        if !coinbase.inputs.is_empty() {
             return Err(BtcError::InvalidTransaction);
         }
 
-        for transaction in &self.transactions {
-            // for every transaction in the block
-            let mut input_value = 0;
-            let mut output_value = 0;
-            // hashmap of current input, later utxo if validated
-            let mut inputs: HashMap<Hash, TransactionOutput> = HashMap::new();
-            for input in &transaction.inputs {
-                // at anytime the utxo set represents the available unspent transaction that we can use as input to spent
-                // meaning that if the current input is not inside the utxo set, it is not a valid transaction
-                let prev_output = utxos.get(&input.prev_transaction_output_hash);
-                if prev_output.is_none() {
-                    return Err(BtcError::InvalidTransaction);
-                }
-                let prev_output = prev_output.unwrap();
-                // prevent double spending in same block
-                if inputs.contains_key(&input.prev_transaction_output_hash) {
-                    return Err(BtcError::InvalidTransaction);
-                }
-                // check if signature is valid. We need to take the public key associated and the previous hash
-                if !input
-                    .signature
-                    .verify(&input.prev_transaction_output_hash, &prev_output.pubkey)
-                {
-                    return Err(BtcError::InvalidTransaction);
-                }
-                // keep track of input values from prev output
-                input_value += prev_output.value;
-                // add it to inputs to prevent double spending
-                inputs.insert(input.prev_transaction_output_hash, prev_output.clone());
-            }
-
-            // At this point, the transactions are almost validated, we just need to check that
-            // the output value is less or equal than the input
+        // A transaction may spend an earlier transaction's output from
+        // within this same block (exactly as the mempool allows spending
+        // another mempool transaction's output), so the spendable set grows
+        // as each transaction is verified rather than staying fixed at
+        // `utxos`.
+        let mut available: HashMap<Txid, TransactionOutput> = utxos.clone();
+        // hashmap of inputs already spent by an earlier transaction in this block
+        let mut spent: HashMap<Txid, TransactionOutput> = HashMap::new();
+        let mut fees = 0u64;
+        for transaction in rest {
+            verify_transaction_lock_time(transaction, height, median_time_past)?;
+            fees += verify_transaction(transaction, &available, &mut spent)?;
             for output in &transaction.outputs {
-                output_value += output.value;
+                available.insert(output.hash(), output.clone());
             }
+        }
 
-            if input_value < output_value {
-                return Err(BtcError::InvalidTransaction);
-            }
+        let coinbase_value: u64 = coinbase.outputs.iter().map(|output| output.value).sum();
+        if coinbase_value > block_subsidy.saturating_add(fees) {
+            return Err(BtcError::InvalidTransaction);
         }
         Ok(())
     }
 }
+
+// A lock_time below this is interpreted as a block height; at or above it, as
+// a unix timestamp. Mirrors Bitcoin's nLockTime rule.
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+// A transaction with a nonzero lock_time cannot be mined until the chain
+// reaches that height (or, for timestamp-style lock times, until the median
+// time past of the last 11 blocks reaches it) unless every input's sequence
+// is SEQUENCE_FINAL, which opts the transaction out of lock time enforcement.
+fn verify_transaction_lock_time(
+    transaction: &Transaction,
+    height: u64,
+    median_time_past: DateTime<Utc>,
+) -> Result<()> {
+    if transaction.lock_time == 0 {
+        return Ok(());
+    }
+    if transaction
+        .inputs
+        .iter()
+        .all(|input| input.sequence == SEQUENCE_FINAL)
+    {
+        return Ok(());
+    }
+    let unlocked = if transaction.lock_time < LOCKTIME_THRESHOLD {
+        height >= transaction.lock_time as u64
+    } else {
+        median_time_past.timestamp() >= transaction.lock_time as i64
+    };
+    if !unlocked {
+        return Err(BtcError::InvalidTransaction);
+    }
+    Ok(())
+}
+
+// Verify a single non-coinbase transaction against the utxo set: every input
+// must reference an existing, not-yet-spent output and carry a script_sig
+// that satisfies that output's script_pubkey, and the total output value
+// must not exceed the total input
+// value. `spent` accumulates the outputs this call consumes, and the caller
+// may reuse it across calls (e.g. across all transactions in a block, or
+// across mempool admission) to catch double spends that span more than one
+// transaction. Returns the transaction's fee (input value minus output
+// value) so callers assembling or validating a block can credit it to the
+// coinbase.
+pub fn verify_transaction(
+    transaction: &Transaction,
+    utxos: &HashMap<Txid, TransactionOutput>,
+    spent: &mut HashMap<Txid, TransactionOutput>,
+) -> Result<u64> {
+    let mut input_value = 0;
+    let mut output_value = 0;
+    for input in &transaction.inputs {
+        // at anytime the utxo set represents the available unspent transaction that we can use as input to spent
+        // meaning that if the current input is not inside the utxo set, it is not a valid transaction
+        let prev_output = utxos.get(&input.prev_transaction_output_hash);
+        if prev_output.is_none() {
+            return Err(BtcError::InvalidTransaction);
+        }
+        let prev_output = prev_output.unwrap();
+        // prevent double spending
+        if spent.contains_key(&input.prev_transaction_output_hash) {
+            return Err(BtcError::InvalidTransaction);
+        }
+        // run script_sig followed by script_pubkey and require a truthy result,
+        // rather than checking a bare signature against a bare public key
+        if !script::verify_script(
+            &input.script_sig,
+            &prev_output.script_pubkey,
+            &input.prev_transaction_output_hash,
+            transaction,
+            input,
+        ) {
+            return Err(BtcError::InvalidTransaction);
+        }
+        // keep track of input values from prev output
+        input_value += prev_output.value;
+        // add it to spent to prevent double spending
+        spent.insert(input.prev_transaction_output_hash, prev_output.clone());
+    }
+
+    // At this point, the transaction is almost validated, we just need to check that
+    // the output value is less or equal than the input
+    for output in &transaction.outputs {
+        output_value += output.value;
+    }
+
+    if input_value < output_value {
+        return Err(BtcError::InvalidTransaction);
+    }
+    Ok(input_value - output_value)
+}
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct BlockHeader {
     // Timestamp of the block
@@ -153,7 +281,7 @@ pub struct BlockHeader {
     pub nonce: u64,
     // we use an array of u8 (each element is a 8-bit integer) with 32 elements
     // meaning we have 32*8=256 bits which correspond to the output of sha256
-    pub prev_block_hash: Hash,
+    pub prev_block_hash: BlockHash,
     pub merkle_root: MerkleRoot,
     // a number representing the difficulty. The target is a 256-bit value that represents
     // the maximum allowed hash value for a valid block. The lower the target value, the harder
@@ -167,7 +295,7 @@ impl BlockHeader {
     pub fn new(
         timestamp: DateTime<Utc>,
         nonce: u64,
-        prev_block_hash: Hash,
+        prev_block_hash: BlockHash,
         merkle_root: MerkleRoot,
         target: U256,
     ) -> Self {
@@ -180,41 +308,124 @@ impl BlockHeader {
         }
     }
 
-    pub fn hash(&self) -> Hash {
-        Hash::hash(self)
+    pub fn hash(&self) -> BlockHash {
+        BlockHash::hash(self)
     }
 }
+// A transaction's format version. Only version 2 is produced by this crate,
+// but the field exists so future rule changes (e.g. relative lock times) have
+// somewhere to hang off of.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Version(pub u32);
+
+impl Version {
+    pub const TWO: Version = Version(2);
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Transaction {
+    pub version: Version,
     pub inputs: Vec<TransactionInput>,
     pub outputs: Vec<TransactionOutput>,
+    // the transaction cannot be mined before this absolute block height or
+    // timestamp (see LOCKTIME_THRESHOLD); 0 means "no lock time"
+    pub lock_time: u32,
 }
 
 impl Transaction {
     pub fn new(inputs: Vec<TransactionInput>, outputs: Vec<TransactionOutput>) -> Self {
-        Self { inputs, outputs }
+        Self {
+            version: Version::TWO,
+            inputs,
+            outputs,
+            lock_time: 0,
+        }
     }
-    pub fn hash(&self) -> Hash {
-        Hash::hash(self)
+    pub fn hash(&self) -> Txid {
+        Txid::hash(self)
     }
 }
+
+// An input whose sequence is SEQUENCE_FINAL opts its transaction out of lock
+// time enforcement entirely, matching Bitcoin's nSequence rule.
+pub const SEQUENCE_FINAL: u32 = 0xFFFFFFFF;
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct TransactionInput {
     // the hash of the transaction output, which we are linking
     // into this transaction as input.
-    pub prev_transaction_output_hash: Hash,
-    // this is how the user proves they can use the output of the previous transaction.
-    pub signature: Signature,
+    pub prev_transaction_output_hash: Txid,
+    // the unlocking script proving the right to spend prev_transaction_output_hash;
+    // run before the referenced output's script_pubkey.
+    pub script_sig: Vec<u8>,
+    // SEQUENCE_FINAL means this input does not opt the transaction into lock
+    // time enforcement.
+    pub sequence: u32,
+}
+
+impl TransactionInput {
+    pub fn new(prev_transaction_output_hash: Txid, script_sig: Vec<u8>) -> Self {
+        Self {
+            prev_transaction_output_hash,
+            script_sig,
+            sequence: SEQUENCE_FINAL,
+        }
+    }
 }
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct TransactionOutput {
     pub value: u64,
     // unique identifier that helps us ensure that the hash of each transaction output is unique.
     pub unique_id: Uuid,
-    pub pubkey: PublicKey,
+    // the locking script a spending input's script_sig must satisfy.
+    pub script_pubkey: Vec<u8>,
 }
 impl TransactionOutput {
-    pub fn hash(&self) -> Hash {
-        Hash::hash(self)
+    pub fn hash(&self) -> Txid {
+        Txid::hash(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn transaction_with(lock_time: u32, sequence: u32) -> Transaction {
+        let mut input = TransactionInput::new(Txid::zero(), vec![]);
+        input.sequence = sequence;
+        let mut transaction = Transaction::new(vec![input], vec![]);
+        transaction.lock_time = lock_time;
+        transaction
+    }
+
+    #[test]
+    fn zero_lock_time_is_never_enforced() {
+        let transaction = transaction_with(0, 0);
+        assert!(verify_transaction_lock_time(&transaction, 0, Utc.timestamp_opt(0, 0).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn sequence_final_opts_out_of_lock_time() {
+        let transaction = transaction_with(1_000, SEQUENCE_FINAL);
+        assert!(verify_transaction_lock_time(&transaction, 0, Utc.timestamp_opt(0, 0).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn height_style_lock_time_is_enforced() {
+        let transaction = transaction_with(100, 0);
+        let now = Utc.timestamp_opt(0, 0).unwrap();
+        assert!(verify_transaction_lock_time(&transaction, 99, now).is_err());
+        assert!(verify_transaction_lock_time(&transaction, 100, now).is_ok());
+    }
+
+    #[test]
+    fn timestamp_style_lock_time_is_enforced() {
+        let lock_time = LOCKTIME_THRESHOLD + 1_000;
+        let transaction = transaction_with(lock_time, 0);
+        let before = Utc.timestamp_opt(lock_time as i64 - 1, 0).unwrap();
+        let after = Utc.timestamp_opt(lock_time as i64, 0).unwrap();
+        assert!(verify_transaction_lock_time(&transaction, 0, before).is_err());
+        assert!(verify_transaction_lock_time(&transaction, 0, after).is_ok());
     }
 }
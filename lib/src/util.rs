@@ -0,0 +1,36 @@
+/*
+Miscellaneous helpers that don't belong to any single entity.
+*/
+
+use crate::sha256::TxMerkleNode;
+use crate::types::Transaction;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MerkleRoot(TxMerkleNode);
+
+impl MerkleRoot {
+    // Calculate the merkle root of a block's transactions: take each
+    // transaction's txid as a leaf as-is, then repeatedly hash pairs of
+    // nodes (duplicating the last one if the layer is odd-sized) until a
+    // single root node remains.
+    pub fn calculate(transactions: &[Transaction]) -> MerkleRoot {
+        let mut layer: Vec<TxMerkleNode> = transactions
+            .iter()
+            .map(|transaction| TxMerkleNode::from(transaction.hash()))
+            .collect();
+        if layer.is_empty() {
+            return MerkleRoot(TxMerkleNode::zero());
+        }
+        while layer.len() > 1 {
+            if !layer.len().is_multiple_of(2) {
+                layer.push(*layer.last().unwrap());
+            }
+            layer = layer
+                .chunks(2)
+                .map(|pair| TxMerkleNode::hash(&(pair[0], pair[1])))
+                .collect();
+        }
+        MerkleRoot(layer[0])
+    }
+}